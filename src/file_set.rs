@@ -6,6 +6,8 @@ use std::path::{Path, PathBuf};
 pub struct FileSet {
     base_dir: PathBuf,
     includes: GlobSet,
+    excludes: GlobSet,
+    respect_gitignore: bool,
 }
 
 impl FileSet {
@@ -14,7 +16,12 @@ impl FileSet {
     }
 
     pub(crate) fn matches(&self, path: impl AsRef<Path>) -> bool {
-        self.includes.is_match(path)
+        let path = path.as_ref();
+        self.includes.is_match(path) && !self.excludes.is_match(path)
+    }
+
+    pub(crate) fn respects_gitignore(&self) -> bool {
+        self.respect_gitignore
     }
 }
 
@@ -23,6 +30,8 @@ impl FileSet {
 pub struct FileSetBuilder {
     base_dir: PathBuf,
     includes: GlobSetBuilder,
+    excludes: GlobSetBuilder,
+    respect_gitignore: bool,
 }
 
 impl FileSetBuilder {
@@ -30,6 +39,8 @@ impl FileSetBuilder {
         Self {
             base_dir,
             includes: GlobSetBuilder::new(),
+            excludes: GlobSetBuilder::new(),
+            respect_gitignore: false,
         }
     }
 
@@ -42,10 +53,36 @@ impl FileSetBuilder {
         self
     }
 
+    /// Defines an exclude pattern.
+    ///
+    /// A path is only part of the file set if it matches at least one include pattern and no
+    /// exclude pattern.
+    ///
+    /// See the [globset documentation](https://docs.rs/globset/0.4.5/globset/#syntax) for details
+    /// about the pattern syntax.
+    pub fn exclude(&mut self, value: impl AsRef<str>) -> &mut Self {
+        self.excludes.add(Glob::new(value.as_ref()).unwrap());
+        self
+    }
+
+    /// Additionally excludes files ignored by `.gitignore`, `.ignore` and global git excludes
+    /// while walking the base directory.
+    ///
+    /// This is useful for mounting a source tree as a user would actually commit it.
+    ///
+    /// `.gitignore` and global git excludes only take effect inside a git working tree, and hidden
+    /// files and directories (names starting with `.`) are skipped as well.
+    pub fn respect_gitignore(&mut self) -> &mut Self {
+        self.respect_gitignore = true;
+        self
+    }
+
     pub(crate) fn build(&self) -> FileSet {
         FileSet {
             base_dir: self.base_dir.clone(),
             includes: self.includes.build().unwrap(),
+            excludes: self.excludes.build().unwrap(),
+            respect_gitignore: self.respect_gitignore,
         }
     }
 }
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Abstracts over the filesystem operations needed to source and render a [`crate::MountSource`]
+/// tree, so trees can be assembled and rendered without touching disk.
+///
+/// The two shipped implementations are [`OsFs`] (the real file system) and [`MemoryFs`] (a purely
+/// virtual, in-memory file system useful for hermetic tests).
+pub trait Fs {
+    /// Recursively walks `path`, yielding an entry for `path` itself and all of its descendants.
+    /// [`MemoryFs`] has no explicit directories, so it yields only the files at or below `path`.
+    fn walk(&self, path: &Path) -> Box<dyn Iterator<Item = FsEntry>>;
+
+    /// Opens the file at `path` for reading.
+    fn open(&self, path: &Path) -> Box<dyn Read + Send>;
+
+    /// Returns metadata about the entry at `path`.
+    fn metadata(&self, path: &Path) -> FsMetadata;
+
+    /// Creates `path` and all of its missing parent directories.
+    fn create_dir(&self, path: &Path);
+
+    /// Creates (or truncates) the file at `path`, creating missing parent directories first, and
+    /// writes the complete contents of `reader` to it.
+    fn create_file(&self, path: &Path, reader: &mut dyn Read);
+}
+
+/// A single entry discovered by [`Fs::walk`].
+pub struct FsEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// Metadata about a single path, as returned by [`Fs::metadata`].
+pub struct FsMetadata {
+    pub is_dir: bool,
+    /// The Unix file mode, if known. Only [`OsFs`] populates this.
+    pub mode: Option<u32>,
+    /// The modification time as Unix epoch seconds, if known. Only [`OsFs`] populates this.
+    pub mtime: Option<u64>,
+}
+
+/// The real, OS-backed file system. This is what [`crate::MountSource`] uses by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsFs;
+
+impl Fs for OsFs {
+    fn walk(&self, path: &Path) -> Box<dyn Iterator<Item = FsEntry>> {
+        let iter = WalkDir::new(path).into_iter().filter_map(|e| e.ok()).map(|e| FsEntry {
+            path: e.path().to_path_buf(),
+            is_dir: e.file_type().is_dir(),
+        });
+        Box::new(iter)
+    }
+
+    fn open(&self, path: &Path) -> Box<dyn Read + Send> {
+        Box::new(File::open(path).unwrap())
+    }
+
+    fn metadata(&self, path: &Path) -> FsMetadata {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::metadata(path).unwrap();
+        FsMetadata {
+            is_dir: metadata.is_dir(),
+            mode: Some(metadata.mode()),
+            mtime: Some(metadata.mtime() as u64),
+        }
+    }
+
+    fn create_dir(&self, path: &Path) {
+        fs::create_dir_all(path).unwrap();
+    }
+
+    fn create_file(&self, path: &Path, reader: &mut dyn Read) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = File::create(path).unwrap();
+        io::copy(reader, &mut file).unwrap();
+    }
+}
+
+/// A purely virtual file system backed by a map from path to file content.
+///
+/// Use this to source a [`crate::MountSource`] tree from generated content, or to render a tree
+/// and snapshot the output map, without touching disk.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    files: RefCell<BTreeMap<PathBuf, Vec<u8>>>,
+}
+
+impl MemoryFs {
+    /// Creates an empty in-memory file system.
+    pub fn new() -> MemoryFs {
+        Default::default()
+    }
+
+    /// Creates an in-memory file system pre-populated with the given files.
+    pub fn with_files(files: BTreeMap<PathBuf, Vec<u8>>) -> MemoryFs {
+        MemoryFs {
+            files: RefCell::new(files),
+        }
+    }
+
+    /// Returns a snapshot of all files currently held by this file system.
+    pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        self.files.borrow().clone()
+    }
+}
+
+impl Fs for MemoryFs {
+    fn walk(&self, path: &Path) -> Box<dyn Iterator<Item = FsEntry>> {
+        let files = self.files.borrow();
+        if files.contains_key(path) {
+            let entry = FsEntry {
+                path: path.to_path_buf(),
+                is_dir: false,
+            };
+            return Box::new(std::iter::once(entry));
+        }
+        let entries: Vec<_> = files
+            .keys()
+            .filter(|p| p.starts_with(path))
+            .map(|p| FsEntry {
+                path: p.clone(),
+                is_dir: false,
+            })
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    fn open(&self, path: &Path) -> Box<dyn Read + Send> {
+        let content = self.files.borrow().get(path).unwrap().clone();
+        Box::new(io::Cursor::new(content))
+    }
+
+    fn metadata(&self, path: &Path) -> FsMetadata {
+        let files = self.files.borrow();
+        FsMetadata {
+            is_dir: !files.contains_key(path),
+            mode: None,
+            mtime: None,
+        }
+    }
+
+    fn create_dir(&self, _path: &Path) {
+        // Directories are implicit in `MemoryFs`: they exist as soon as a file below them exists.
+    }
+
+    fn create_file(&self, path: &Path, reader: &mut dyn Read) {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+        self.files.borrow_mut().insert(path.to_path_buf(), content);
+    }
+}
@@ -0,0 +1,188 @@
+use std::io;
+use std::io::Read;
+
+/// How line endings should be normalized while streaming a text file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    /// Leave line endings untouched.
+    Preserve,
+    /// Normalize every line ending to `\n`.
+    Lf,
+    /// Normalize every line ending to `\r\n`.
+    Crlf,
+    /// Detect the dominant line ending in each file and normalize to it.
+    Auto,
+}
+
+/// How many leading bytes are inspected to sniff whether a file is binary (contains a NUL byte)
+/// and, for [`LineEnding::Auto`], to determine the dominant line ending.
+const SNIFF_LEN: usize = 8000;
+
+/// Wraps `reader` so that, unless `line_ending` is [`LineEnding::Preserve`] or the content looks
+/// binary (a NUL byte turns up in the first [`SNIFF_LEN`] bytes), its line endings are normalized
+/// on the fly, without ever buffering the whole file.
+pub(crate) fn normalize(
+    reader: Box<dyn Read + Send>,
+    line_ending: LineEnding,
+) -> Box<dyn Read + Send> {
+    if line_ending == LineEnding::Preserve {
+        reader
+    } else {
+        Box::new(LineEndingReader {
+            inner: reader,
+            line_ending,
+            state: None,
+        })
+    }
+}
+
+struct LineEndingReader {
+    inner: Box<dyn Read + Send>,
+    line_ending: LineEnding,
+    state: Option<State>,
+}
+
+struct State {
+    /// `None` means the content was sniffed as binary, so bytes pass through untouched.
+    target: Option<&'static [u8]>,
+    buffered: Vec<u8>,
+    pos: usize,
+    pending_cr: bool,
+    eof: bool,
+}
+
+impl LineEndingReader {
+    fn init(&mut self) -> io::Result<&mut State> {
+        if self.state.is_none() {
+            let mut prefix = Vec::with_capacity(SNIFF_LEN);
+            let mut chunk = [0u8; 1024];
+            let mut eof = false;
+            while prefix.len() < SNIFF_LEN {
+                let n = self.inner.read(&mut chunk)?;
+                if n == 0 {
+                    eof = true;
+                    break;
+                }
+                prefix.extend_from_slice(&chunk[..n]);
+            }
+            let is_binary = prefix.contains(&0);
+            let target = if is_binary {
+                None
+            } else {
+                Some(match self.line_ending {
+                    LineEnding::Lf => &b"\n"[..],
+                    LineEnding::Crlf => &b"\r\n"[..],
+                    LineEnding::Auto => {
+                        let crlf_count = prefix.windows(2).filter(|w| *w == b"\r\n").count();
+                        let lf_count = prefix.iter().filter(|&&b| b == b'\n').count();
+                        if crlf_count * 2 >= lf_count {
+                            &b"\r\n"[..]
+                        } else {
+                            &b"\n"[..]
+                        }
+                    }
+                    LineEnding::Preserve => unreachable!("handled by normalize()"),
+                })
+            };
+            self.state = Some(State {
+                target,
+                buffered: prefix,
+                pos: 0,
+                pending_cr: false,
+                eof,
+            });
+        }
+        Ok(self.state.as_mut().unwrap())
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        let state = self.state.as_mut().unwrap();
+        if state.eof || state.pos < state.buffered.len() {
+            return Ok(());
+        }
+        let mut chunk = [0u8; 4096];
+        let n = self.inner.read(&mut chunk)?;
+        if n == 0 {
+            state.eof = true;
+        } else {
+            state.buffered = chunk[..n].to_vec();
+            state.pos = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Read for LineEndingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.init()?;
+        self.refill()?;
+        let state = self.state.as_mut().unwrap();
+
+        let Some(target) = state.target else {
+            let available = &state.buffered[state.pos..];
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            state.pos += n;
+            return Ok(n);
+        };
+
+        if state.pos >= state.buffered.len() && state.eof {
+            if state.pending_cr {
+                if target.len() > buf.len() {
+                    // Not enough room to flush the trailing lone `\r` this call; leave
+                    // `pending_cr` set and retry on the next (hopefully larger) read.
+                    return Ok(0);
+                }
+                state.pending_cr = false;
+                buf[..target.len()].copy_from_slice(target);
+                return Ok(target.len());
+            }
+            return Ok(0);
+        }
+
+        let mut written = 0;
+        while written < buf.len() && state.pos < state.buffered.len() {
+            let b = state.buffered[state.pos];
+            if b == b'\r' {
+                if state.pending_cr {
+                    // A second bare `\r` in a row: the previous one was already a line break on
+                    // its own (it wasn't followed by `\n`), so flush it before tracking this one.
+                    if written + target.len() > buf.len() {
+                        break;
+                    }
+                    buf[written..written + target.len()].copy_from_slice(target);
+                    written += target.len();
+                }
+                state.pos += 1;
+                state.pending_cr = true;
+                continue;
+            }
+            if b == b'\n' {
+                if written + target.len() > buf.len() {
+                    break;
+                }
+                state.pos += 1;
+                state.pending_cr = false;
+                buf[written..written + target.len()].copy_from_slice(target);
+                written += target.len();
+                continue;
+            }
+            if state.pending_cr {
+                if written + target.len() > buf.len() {
+                    break;
+                }
+                state.pending_cr = false;
+                buf[written..written + target.len()].copy_from_slice(target);
+                written += target.len();
+                continue;
+            }
+            state.pos += 1;
+            buf[written] = b;
+            written += 1;
+        }
+        Ok(written)
+    }
+}
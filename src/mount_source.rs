@@ -1,15 +1,64 @@
+use crate::fs::{Fs, FsEntry, OsFs};
+use crate::line_ending::{self, LineEnding};
 use crate::{FileSet, FileSetBuilder};
 use core::iter;
 use flate2::write::GzEncoder;
 use globset::Glob;
-use std::fs;
+use rayon::prelude::*;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
 use std::iter::once;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
 use tar::{EntryType, Header};
-use walkdir::WalkDir;
+
+/// Compression level (and, for xz, dictionary size) used when rendering an archive.
+///
+/// For [`MountSource::render_to_tar_gz`] and [`MountSource::render_to_tar_xz`], `level` follows the
+/// 0 (fastest/largest) to 9 (slowest/smallest) scale used by gzip and xz. For
+/// [`MountSource::render_to_tar_zst`], zstd's own scale applies instead, which runs up to roughly
+/// 22 for a substantially better ratio at the cost of much slower encoding; the default of 6 is a
+/// reasonable middle ground on all three scales.
+#[derive(Clone, Debug)]
+pub struct CompressionOptions {
+    pub level: u32,
+    /// LZMA dictionary size in bytes, only used by [`MountSource::render_to_tar_xz`]. Raising it
+    /// (e.g. from 8 MB to 64 MB) shrinks the archive at the cost of more memory while encoding.
+    pub xz_dict_size: u32,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            level: 6,
+            xz_dict_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// Progress event emitted while rendering an archive in parallel, e.g. via
+/// [`MountSource::render_to_tar_gz_parallel`].
+#[derive(Clone, Debug)]
+pub enum RenderEvent {
+    /// A file or directory started being read.
+    EntryStarted(PathBuf),
+    /// A file or directory finished being read into memory, and is ready to be written (and
+    /// compressed) into the archive. `bytes` is its uncompressed size.
+    EntryFinished { path: PathBuf, bytes: u64 },
+    /// All entries have been written to the archive.
+    Done,
+}
+
+/// A final archive path claimed by more than one mount, as reported by [`MountSource::validate`].
+#[derive(Clone, Debug)]
+pub struct PathCollision {
+    /// The path within the rendered tree that is claimed more than once.
+    pub path: PathBuf,
+    /// The mount points of all mounts that claim `path`.
+    pub mount_points: Vec<PathBuf>,
+}
 
 /// Describes a single file, a single directory or a complete directory tree that should get mounted
 /// into a user-defined directory structure.
@@ -23,44 +72,88 @@ pub enum MountSource {
     TextContent(String),
     /// Copies a partial directory tree from the file system based on include patterns.
     FileSet(FileSet),
+    /// Re-exposes the entries of an existing `.tar`, `.tar.gz` or `.zip` archive, rooted at the
+    /// mount point.
+    MergeArchive(PathBuf),
 }
 
 impl MountSource {
     /// Creates this directory structure on the file system in the specified target directory.
     pub fn render_to_fs(&self, target_dir: impl AsRef<Path>) {
-        for mut w in self.walk_virtual_files() {
+        self.render_to_fs_with(&OsFs, LineEnding::Preserve, target_dir);
+    }
+
+    /// Like [`Self::render_to_fs`], but sources and writes the tree through the given [`Fs`]
+    /// backend instead of the real file system, and normalizes text files' line endings on the
+    /// way through according to `line_ending`.
+    ///
+    /// This allows rendering a tree made up of purely virtual inputs and, e.g. with a
+    /// [`crate::fs::MemoryFs`], snapshotting the resulting files without touching disk.
+    pub fn render_to_fs_with(
+        &self,
+        fs: &dyn Fs,
+        line_ending: LineEnding,
+        target_dir: impl AsRef<Path>,
+    ) {
+        for mut w in self.walk_virtual_files(fs, line_ending) {
             let absolute_path = target_dir.as_ref().join(&w.path);
             if w.is_dir {
-                fs::create_dir_all(absolute_path);
+                fs.create_dir(&absolute_path);
             } else {
-                fs::create_dir_all(absolute_path.parent().unwrap());
-                let mut file = File::create(absolute_path).unwrap();
-                io::copy(&mut *w.reader, &mut file);
+                fs.create_file(&absolute_path, &mut *w.reader);
             }
         }
     }
 
-    /// Creates this directory structure as a ZIP file.
+    /// Creates this directory structure as a ZIP file, deflated at the default compression level.
     pub fn render_to_zip(&self, zip_file: impl AsRef<Path>) {
+        self.render_to_zip_with(
+            &OsFs,
+            zip_file,
+            zip::CompressionMethod::Deflated,
+            &CompressionOptions::default(),
+            false,
+        );
+    }
+
+    /// Like [`Self::render_to_zip`], but sources the tree through the given [`Fs`] backend instead
+    /// of the real file system (e.g. a [`crate::fs::MemoryFs`]), and with a configurable
+    /// compression method and level. When `reproducible` is true, every entry's modification time
+    /// is pinned to a fixed value instead of whatever [`VirtualFile::mtime`] it carries, so
+    /// re-rendering the same tree produces a byte-identical ZIP. That value is the Unix epoch,
+    /// which ZIP's MS-DOS timestamps cannot represent, so it is stored as 1980-01-01 00:00:00.
+    pub fn render_to_zip_with(
+        &self,
+        fs: &dyn Fs,
+        zip_file: impl AsRef<Path>,
+        method: zip::CompressionMethod,
+        options: &CompressionOptions,
+        reproducible: bool,
+    ) {
         let zip_file = File::create(zip_file).unwrap();
         let mut zip = zip::ZipWriter::new(zip_file);
-        let options = zip::write::FileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated)
-            .unix_permissions(0o755);
+        let base_options = zip::write::FileOptions::default()
+            .compression_method(method)
+            .compression_level(Some(options.level as i32));
         let mut buffer = Vec::new();
-        for mut w in self.walk_virtual_files() {
+        for mut w in self.walk_virtual_files(fs, LineEnding::Preserve) {
+            let mtime = if reproducible { Some(0) } else { w.mtime };
+            let mut file_options = base_options.unix_permissions(w.mode.unwrap_or(0o755));
+            if let Some(mtime) = mtime {
+                file_options = file_options.last_modified_time(unix_time_to_zip_datetime(mtime));
+            }
             if w.is_dir {
                 if w.path.as_os_str().is_empty() {
                     // Ignore root.
                     continue;
                 }
                 // The "dir" case is important for empty directories only. See comment below.
-                zip.add_directory_from_path(&w.path, options).unwrap();
+                zip.add_directory_from_path(&w.path, file_options).unwrap();
             } else {
                 // When file sets are used, it's possible that the walker visits files whose
                 // parent directory has not been visited. That's not an issue when creating the ZIP
                 // archive. The directory will be created automatically.
-                zip.start_file_from_path(&w.path, options).unwrap();
+                zip.start_file_from_path(&w.path, file_options).unwrap();
                 w.reader.read_to_end(&mut buffer).unwrap();
                 zip.write_all(&*buffer).unwrap();
                 buffer.clear();
@@ -68,13 +161,189 @@ impl MountSource {
         }
     }
 
-    /// Creates this directory structure as a gzipped tarball.
+    /// Creates this directory structure as a gzipped tarball, at the default compression level.
     pub fn render_to_tar_gz(&self, archive_path: impl AsRef<Path>) {
+        self.render_to_tar_gz_with(&OsFs, archive_path, &CompressionOptions::default(), false);
+    }
+
+    /// Like [`Self::render_to_tar_gz`], but sources the tree through the given [`Fs`] backend
+    /// instead of the real file system (e.g. a [`crate::fs::MemoryFs`]), and with a configurable
+    /// compression level. When `reproducible` is true, every entry's modification time is pinned
+    /// to the Unix epoch, so re-rendering the same tree produces a byte-identical tarball.
+    pub fn render_to_tar_gz_with(
+        &self,
+        fs: &dyn Fs,
+        archive_path: impl AsRef<Path>,
+        options: &CompressionOptions,
+        reproducible: bool,
+    ) {
+        let archive_file = File::create(archive_path).unwrap();
+        let enc = GzEncoder::new(archive_file, flate2::Compression::new(options.level));
+        let mut tar = tar::Builder::new(enc);
+        self.write_tar_entries(fs, &mut tar, reproducible);
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    /// Creates this directory structure as an xz-compressed tarball, at the default compression
+    /// level and dictionary size.
+    pub fn render_to_tar_xz(&self, archive_path: impl AsRef<Path>) {
+        self.render_to_tar_xz_with(&OsFs, archive_path, &CompressionOptions::default(), false);
+    }
+
+    /// Like [`Self::render_to_tar_xz`], but sources the tree through the given [`Fs`] backend
+    /// instead of the real file system (e.g. a [`crate::fs::MemoryFs`]), and with a configurable
+    /// compression level and LZMA dictionary size. Raising the dictionary size shrinks the archive
+    /// at the cost of more memory while encoding. When `reproducible` is true, every entry's
+    /// modification time is pinned to the Unix epoch, so re-rendering the same tree produces a
+    /// byte-identical tarball.
+    pub fn render_to_tar_xz_with(
+        &self,
+        fs: &dyn Fs,
+        archive_path: impl AsRef<Path>,
+        options: &CompressionOptions,
+        reproducible: bool,
+    ) {
+        let archive_file = File::create(archive_path).unwrap();
+        let mut lzma_options = xz2::stream::LzmaOptions::new_preset(options.level).unwrap();
+        lzma_options.dict_size(options.xz_dict_size);
+        let mut filters = xz2::stream::Filters::new();
+        filters.lzma2(&lzma_options);
+        let stream =
+            xz2::stream::Stream::new_stream_encoder(&filters, xz2::stream::Check::Crc64).unwrap();
+        let enc = xz2::write::XzEncoder::new_stream(archive_file, stream);
+        let mut tar = tar::Builder::new(enc);
+        self.write_tar_entries(fs, &mut tar, reproducible);
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    /// Creates this directory structure as a zstd-compressed tarball, at the default compression
+    /// level.
+    pub fn render_to_tar_zst(&self, archive_path: impl AsRef<Path>) {
+        self.render_to_tar_zst_with(&OsFs, archive_path, &CompressionOptions::default(), false);
+    }
+
+    /// Like [`Self::render_to_tar_zst`], but sources the tree through the given [`Fs`] backend
+    /// instead of the real file system (e.g. a [`crate::fs::MemoryFs`]), and with a configurable
+    /// compression level. When `reproducible` is true, every entry's modification time is pinned
+    /// to the Unix epoch, so re-rendering the same tree produces a byte-identical tarball.
+    pub fn render_to_tar_zst_with(
+        &self,
+        fs: &dyn Fs,
+        archive_path: impl AsRef<Path>,
+        options: &CompressionOptions,
+        reproducible: bool,
+    ) {
+        let archive_file = File::create(archive_path).unwrap();
+        let enc = zstd::stream::write::Encoder::new(archive_file, options.level as i32).unwrap();
+        let mut tar = tar::Builder::new(enc);
+        self.write_tar_entries(fs, &mut tar, reproducible);
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    /// Like [`Self::render_to_tar_gz`], but sources the tree through the given [`Fs`] backend
+    /// instead of the real file system (e.g. a [`crate::fs::MemoryFs`]), and reads file contents
+    /// across a rayon thread pool before feeding the finished entries to the (necessarily
+    /// single-threaded) tar writer. Gzip compression is not parallelized: it happens on that
+    /// single writer as entries are appended. `progress`, if given, receives a [`RenderEvent`]
+    /// for every entry plus a final [`RenderEvent::Done`], so callers can drive a progress bar.
+    pub fn render_to_tar_gz_parallel(
+        &self,
+        fs: &dyn Fs,
+        archive_path: impl AsRef<Path>,
+        options: &CompressionOptions,
+        reproducible: bool,
+        progress: Option<Sender<RenderEvent>>,
+    ) {
         let archive_file = File::create(archive_path).unwrap();
-        let enc = GzEncoder::new(archive_file, flate2::Compression::default());
+        let enc = GzEncoder::new(archive_file, flate2::Compression::new(options.level));
         let mut tar = tar::Builder::new(enc);
+        self.write_tar_entries_parallel(fs, &mut tar, reproducible, progress);
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    /// Reads this tree's entries into memory across a rayon thread pool, then appends them to
+    /// `tar` (which must stay single-threaded, along with any encoder it wraps) in the order they
+    /// were discovered. When
+    /// `reproducible` is true, every entry's modification time is pinned to the Unix epoch instead
+    /// of whatever [`VirtualFile::mtime`] it carries.
+    fn write_tar_entries_parallel<W: Write>(
+        &self,
+        fs: &dyn Fs,
+        tar: &mut tar::Builder<W>,
+        reproducible: bool,
+        progress: Option<Sender<RenderEvent>>,
+    ) {
+        let virtual_files: Vec<VirtualFile> = self
+            .walk_virtual_files(fs, LineEnding::Preserve)
+            // Ignore root. Filtered out here already so it doesn't show up in `progress` either.
+            .filter(|w| !(w.is_dir && w.path.as_os_str().is_empty()))
+            .collect();
+        let entries: Vec<(PathBuf, bool, Vec<u8>, Option<u32>, Option<u64>)> = virtual_files
+            .into_par_iter()
+            .map(|mut w| {
+                if reproducible {
+                    w.mtime = Some(0);
+                }
+                if let Some(tx) = &progress {
+                    tx.send(RenderEvent::EntryStarted(w.path.clone())).ok();
+                }
+                let mut buffer = Vec::new();
+                if !w.is_dir {
+                    w.reader.read_to_end(&mut buffer).unwrap();
+                }
+                if let Some(tx) = &progress {
+                    tx.send(RenderEvent::EntryFinished {
+                        path: w.path.clone(),
+                        bytes: buffer.len() as u64,
+                    })
+                    .ok();
+                }
+                (w.path, w.is_dir, buffer, w.mode, w.mtime)
+            })
+            .collect();
+        for (path, is_dir, buffer, mode, mtime) in entries {
+            if is_dir {
+                let mut header = Header::new_gnu();
+                header.set_entry_type(EntryType::Directory);
+                header.set_size(0);
+                if let Some(mode) = mode {
+                    header.set_mode(mode);
+                }
+                if let Some(mtime) = mtime {
+                    header.set_mtime(mtime);
+                }
+                tar.append_data(&mut header, &path, io::empty()).unwrap();
+            } else {
+                let mut header = Header::new_gnu();
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(buffer.len() as _);
+                if let Some(mode) = mode {
+                    header.set_mode(mode);
+                }
+                if let Some(mtime) = mtime {
+                    header.set_mtime(mtime);
+                }
+                tar.append_data(&mut header, &path, buffer.as_slice())
+                    .unwrap();
+            }
+        }
+        if let Some(tx) = &progress {
+            tx.send(RenderEvent::Done).ok();
+        }
+    }
+
+    /// Walks this tree and appends every entry to `tar`, leaving the choice of codec wrapping the
+    /// underlying writer to the caller. When `reproducible` is true, every entry's modification
+    /// time is pinned to the Unix epoch instead of whatever [`VirtualFile::mtime`] it carries.
+    fn write_tar_entries<W: Write>(
+        &self,
+        fs: &dyn Fs,
+        tar: &mut tar::Builder<W>,
+        reproducible: bool,
+    ) {
         let mut buffer = Vec::new();
-        for mut w in self.walk_virtual_files() {
+        for mut w in self.walk_virtual_files(fs, LineEnding::Preserve) {
+            let mtime = if reproducible { Some(0) } else { w.mtime };
             if w.is_dir {
                 if w.path.as_os_str().is_empty() {
                     // Ignore root.
@@ -84,7 +353,13 @@ impl MountSource {
                 let mut header = Header::new_gnu();
                 header.set_entry_type(EntryType::Directory);
                 header.set_size(0);
-                tar.append_data(&mut header, w.path, io::empty()).unwrap();
+                if let Some(mode) = w.mode {
+                    header.set_mode(mode);
+                }
+                if let Some(mtime) = mtime {
+                    header.set_mtime(mtime);
+                }
+                tar.append_data(&mut header, &w.path, io::empty()).unwrap();
             } else {
                 // When file sets are used, it's possible that the walker visits files whose
                 // parent directory has not been visited. That's not an issue when creating the tar
@@ -93,28 +368,48 @@ impl MountSource {
                 let mut header = Header::new_gnu();
                 header.set_entry_type(EntryType::Regular);
                 header.set_size(buffer.len() as _);
-                tar.append_data(&mut header, w.path, buffer.as_slice())
+                if let Some(mode) = w.mode {
+                    header.set_mode(mode);
+                }
+                if let Some(mtime) = mtime {
+                    header.set_mtime(mtime);
+                }
+                tar.append_data(&mut header, &w.path, buffer.as_slice())
                     .unwrap();
                 buffer.clear();
             }
         }
-        tar.finish().unwrap();
     }
 
     /// Returns an iterator that recursively walks over all defined mounts (depth-first).
     ///
     /// Starts with this mount source mounted at root ("").
     fn walk_mounts<'a>(&'a self) -> impl Iterator<Item = Mount<'a>> + 'a {
-        self.walk_recursive(PathBuf::from(""))
+        self.walk_recursive(PathBuf::from(""), EntryOverrides::default())
     }
 
     /// Returns an iterator that recursively expands all desired mounts into concrete directories
     /// and files (depth-first).
     ///
-    /// Starts with this mount source mounted at root ("").
-    fn walk_virtual_files<'a>(&'a self) -> impl Iterator<Item = VirtualFile> + 'a {
+    /// Starts with this mount source mounted at root (""). All reading (and, for
+    /// [`Self::render_to_fs_with`], writing) is routed through `fs`, except the directory walk of
+    /// a gitignore-respecting file set (see [`walkdir_gitignore`]), and text files are passed
+    /// through [`line_ending::normalize`] according to `line_ending`.
+    fn walk_virtual_files<'a>(
+        &'a self,
+        fs: &'a dyn Fs,
+        line_ending: LineEnding,
+    ) -> impl Iterator<Item = VirtualFile> + 'a {
         self.walk_mounts()
-            .map(|m| m.source.resolve_virtual_files(m.point))
+            .map(move |m| {
+                let overrides = m.overrides;
+                m.source
+                    .resolve_virtual_files(m.point, fs, line_ending)
+                    .map(move |mut w| {
+                        overrides.apply(&mut w);
+                        w
+                    })
+            })
             .flatten()
     }
 
@@ -122,45 +417,158 @@ impl MountSource {
     fn resolve_virtual_files<'a>(
         &'a self,
         mount_point: PathBuf,
+        fs: &'a dyn Fs,
+        line_ending: LineEnding,
     ) -> Box<dyn Iterator<Item = VirtualFile> + 'a> {
         match self {
             MountSource::CopyFromPath(p) => {
-                if p.is_dir() {
-                    let iter = walkdir(p)
-                        .map(move |e| create_virtual_file_from_dir_entry(e, &mount_point, p));
+                if fs.metadata(p).is_dir {
+                    let iter = fs.walk(p).map(move |e| {
+                        create_virtual_file_from_fs_entry(e, &mount_point, p, fs, line_ending)
+                    });
                     Box::new(iter)
                 } else {
-                    let wish = VirtualFile::file(mount_point, File::open(p).unwrap());
+                    let wish =
+                        VirtualFile::file(mount_point, line_ending::normalize(fs.open(p), line_ending));
                     Box::new(iter::once(wish))
                 }
             }
             MountSource::CustomDir(_) => Box::new(iter::once(VirtualFile::dir(mount_point))),
             MountSource::TextContent(text) => {
-                let wish = VirtualFile::file(mount_point, io::Cursor::new(text.clone()));
+                let reader: Box<dyn Read + Send> = Box::new(io::Cursor::new(text.clone()));
+                let wish = VirtualFile::file(mount_point, line_ending::normalize(reader, line_ending));
                 Box::new(iter::once(wish))
             }
             MountSource::FileSet(set) => {
                 let base_dir = set.base_dir();
-                let iter = walkdir(base_dir)
-                    .filter(move |e| set.matches(e.path()))
-                    .map(move |e| create_virtual_file_from_dir_entry(e, &mount_point, base_dir));
+                if set.respects_gitignore() {
+                    let iter = walkdir_gitignore(base_dir).filter(move |e| set.matches(e.path())).map(
+                        move |e| {
+                            create_virtual_file_from_dir_entry(
+                                e,
+                                &mount_point,
+                                base_dir,
+                                fs,
+                                line_ending,
+                            )
+                        },
+                    );
+                    Box::new(iter)
+                } else {
+                    let iter = fs.walk(base_dir).filter(move |e| set.matches(&e.path)).map(
+                        move |e| {
+                            create_virtual_file_from_fs_entry(
+                                e,
+                                &mount_point,
+                                base_dir,
+                                fs,
+                                line_ending,
+                            )
+                        },
+                    );
+                    Box::new(iter)
+                }
+            }
+            MountSource::MergeArchive(p) => {
+                let iter = merged_archive_entries(p, fs).into_iter().map(move |e| {
+                    let full_path = mount_point.join(&e.path);
+                    if e.is_dir {
+                        VirtualFile::dir(full_path)
+                    } else {
+                        VirtualFile::file(full_path, io::Cursor::new(e.content))
+                    }
+                });
                 Box::new(iter)
             }
         }
     }
 
+    /// Returns the file name of every archive merged into this tree via [`merge`], in mount order.
+    fn merged_component_names(&self) -> Vec<String> {
+        self.walk_mounts()
+            .filter_map(|m| match m.wish() {
+                MountSource::MergeArchive(p) => {
+                    p.file_name().map(|n| n.to_string_lossy().into_owned())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Wraps this tree so that, in addition to its existing content, a `components` text file is
+    /// generated at the tree root listing the file name of every archive merged in via [`merge`].
+    pub fn with_components_manifest(self) -> MountSource {
+        let manifest = self.merged_component_names().join("\n");
+        let manifest_entry =
+            CustomDirEntry::new(PathBuf::from("components"), MountSource::TextContent(manifest));
+        match self {
+            MountSource::CustomDir(mut entries) => {
+                entries.push(manifest_entry);
+                MountSource::CustomDir(entries)
+            }
+            other => MountSource::CustomDir(vec![
+                CustomDirEntry::new(PathBuf::new(), other),
+                manifest_entry,
+            ]),
+        }
+    }
+
+    /// Checks whether two or more mounts in this tree would claim the same final path, which
+    /// otherwise results in silent last-writer-wins behavior that differs subtly between the
+    /// fs/zip/tar renderers.
+    ///
+    /// Sources the tree through the given [`Fs`] backend instead of the real file system, e.g. a
+    /// [`crate::fs::MemoryFs`].
+    pub fn validate(&self, fs: &dyn Fs) -> Result<(), Vec<PathCollision>> {
+        let mut claims: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for mount in self.walk_mounts() {
+            for w in mount
+                .source
+                .resolve_virtual_files(mount.point.clone(), fs, LineEnding::Preserve)
+            {
+                claims.entry(w.path).or_default().push(mount.point.clone());
+            }
+        }
+        let collisions: Vec<PathCollision> = claims
+            .into_iter()
+            .filter(|(_, mount_points)| mount_points.len() > 1)
+            .map(|(path, mount_points)| PathCollision { path, mount_points })
+            .collect();
+        if collisions.is_empty() {
+            Ok(())
+        } else {
+            Err(collisions)
+        }
+    }
+
+    /// Finds the mount that would produce the final archive path `path`, if any. Useful for
+    /// answering "which mount produced this entry?" before committing a tree to disk.
+    ///
+    /// Sources the tree through the given [`Fs`] backend instead of the real file system, e.g. a
+    /// [`crate::fs::MemoryFs`].
+    pub fn classify(&self, fs: &dyn Fs, path: impl AsRef<Path>) -> Option<Mount<'_>> {
+        let path = path.as_ref();
+        self.walk_mounts().find(|mount| {
+            mount
+                .source
+                .resolve_virtual_files(mount.point.clone(), fs, LineEnding::Preserve)
+                .any(|w| w.path == path)
+        })
+    }
+
     fn walk_recursive<'a>(
         &'a self,
         mount_point: PathBuf,
+        overrides: EntryOverrides,
     ) -> Box<dyn Iterator<Item = Mount<'a>> + 'a> {
-        let current_iter = once(Mount::new(mount_point.clone(), self));
+        let current_iter = once(Mount::new(mount_point.clone(), self, overrides));
         if let MountSource::CustomDir(entries) = self {
             let entry_iter = entries
                 .iter()
                 .map(move |entry| {
                     entry
                         .mount_source
-                        .walk_recursive(mount_point.join(&entry.name))
+                        .walk_recursive(mount_point.join(&entry.name), entry.overrides())
                 })
                 .flatten();
             Box::new(current_iter.chain(entry_iter))
@@ -170,33 +578,264 @@ impl MountSource {
     }
 }
 
-fn walkdir(base_dir: &Path) -> impl Iterator<Item = walkdir::DirEntry> {
-    WalkDir::new(base_dir).into_iter().filter_map(|e| e.ok())
+/// A single entry read out of an archive being merged via [`merge`].
+struct MergedEntry {
+    path: PathBuf,
+    is_dir: bool,
+    content: Vec<u8>,
+}
+
+/// Reads all entries out of the `.tar`, `.tar.gz` or `.zip` archive at `path`, fully buffering
+/// each entry's content. Entries whose stored path is absolute or escapes upward via `..` are
+/// dropped (see [`sanitized_archive_entry_path`]) instead of being merged in verbatim.
+///
+/// The archive itself is read through `fs` (fully buffered first, since `zip::ZipArchive` needs a
+/// seekable reader and [`Fs::open`] only promises `Read`), so merging into a tree sourced from a
+/// [`crate::fs::MemoryFs`] stays hermetic.
+fn merged_archive_entries(path: &Path, fs: &dyn Fs) -> Vec<MergedEntry> {
+    let file_name = path.to_string_lossy();
+    let mut bytes = Vec::new();
+    fs.open(path).read_to_end(&mut bytes).unwrap();
+    if file_name.ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes)).unwrap();
+        (0..archive.len())
+            .filter_map(|i| {
+                let mut entry = archive.by_index(i).unwrap();
+                let path = sanitized_archive_entry_path(Path::new(entry.name()))?;
+                let is_dir = entry.is_dir();
+                let mut content = Vec::new();
+                if !is_dir {
+                    entry.read_to_end(&mut content).unwrap();
+                }
+                Some(MergedEntry {
+                    path,
+                    is_dir,
+                    content,
+                })
+            })
+            .collect()
+    } else {
+        let reader: Box<dyn Read> = if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz")
+        {
+            Box::new(flate2::read::GzDecoder::new(io::Cursor::new(bytes)))
+        } else {
+            Box::new(io::Cursor::new(bytes))
+        };
+        let mut archive = tar::Archive::new(reader);
+        archive
+            .entries()
+            .unwrap()
+            .filter_map(|entry| {
+                let mut entry = entry.unwrap();
+                let is_dir = entry.header().entry_type().is_dir();
+                let entry_path = entry.path().unwrap().into_owned();
+                let path = sanitized_archive_entry_path(&entry_path)?;
+                let mut content = Vec::new();
+                if !is_dir {
+                    entry.read_to_end(&mut content).unwrap();
+                }
+                Some(MergedEntry {
+                    path,
+                    is_dir,
+                    content,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Defends against Zip Slip / tar path traversal: an archive entry whose stored path is absolute
+/// or contains a `..` component would, if joined onto a mount point as-is, write outside the
+/// rendered tree. Returns `None` for such entries so they can be dropped instead of merged in;
+/// otherwise returns the path rebuilt from its normal components only.
+fn sanitized_archive_entry_path(path: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => sanitized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(sanitized)
+}
+
+/// Converts Unix epoch seconds to the MS-DOS-based timestamp used by ZIP entries, so
+/// [`MountSource::render_to_zip_with`] can honor a [`VirtualFile`]'s `mtime`.
+fn unix_time_to_zip_datetime(epoch_secs: u64) -> zip::DateTime {
+    let days = (epoch_secs / 86400) as i64;
+    let secs_of_day = epoch_secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    let hour = (secs_of_day / 3600) as u32;
+    let minute = (secs_of_day % 3600 / 60) as u32;
+    let second = (secs_of_day % 60) as u32;
+    zip::DateTime::from_date_and_time(
+        year.clamp(1980, 2107) as u16,
+        month as u8,
+        day as u8,
+        hour as u8,
+        minute as u8,
+        second as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Converts a day count since the Unix epoch (1970-01-01) to a (year, month, day) civil date,
+/// using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = (z - era * 146097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (y + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+/// Recursively walks `base_dir`, honoring `.gitignore`, `.ignore` and global git excludes found
+/// along the way.
+///
+/// Always walks the real file system rather than going through [`Fs`]: gitignore semantics are
+/// inherently tied to an on-disk working tree, so this is unaffected by whatever [`Fs`] backend a
+/// [`MountSource`] is otherwise rendered through. Note also that `ignore`'s [`ignore::WalkBuilder`]
+/// defaults to requiring a `.git` directory above `base_dir` before it will honor `.gitignore` and
+/// global git excludes at all, so outside of a git working tree
+/// [`FileSetBuilder::respect_gitignore`](crate::FileSetBuilder::respect_gitignore) only applies
+/// `.ignore` files. Its defaults also skip hidden files and directories (names starting with `.`).
+fn walkdir_gitignore(base_dir: &Path) -> impl Iterator<Item = ignore::DirEntry> {
+    ignore::WalkBuilder::new(base_dir)
+        .build()
+        .filter_map(|e| e.ok())
 }
 
 fn create_virtual_file_from_dir_entry(
-    entry: walkdir::DirEntry,
+    entry: impl DirEntryLike,
     mount_point: &Path,
     base_dir: &Path,
+    fs: &dyn Fs,
+    line_ending: LineEnding,
 ) -> VirtualFile {
     let full_path = mount_point.join(entry.path().strip_prefix(base_dir).unwrap());
-    if entry.file_type().is_dir() {
-        VirtualFile::dir(full_path)
+    let mode = entry.mode();
+    let mtime = entry.mtime();
+    if entry.is_dir() {
+        VirtualFile::dir(full_path).with_metadata(mode, mtime)
     } else {
-        VirtualFile::file(full_path, File::open(entry.path()).unwrap())
+        VirtualFile::file(full_path, line_ending::normalize(fs.open(entry.path()), line_ending))
+            .with_metadata(mode, mtime)
+    }
+}
+
+fn create_virtual_file_from_fs_entry(
+    entry: FsEntry,
+    mount_point: &Path,
+    base_dir: &Path,
+    fs: &dyn Fs,
+    line_ending: LineEnding,
+) -> VirtualFile {
+    let full_path = mount_point.join(entry.path.strip_prefix(base_dir).unwrap());
+    let metadata = fs.metadata(&entry.path);
+    if entry.is_dir {
+        VirtualFile::dir(full_path).with_metadata(metadata.mode, metadata.mtime)
+    } else {
+        VirtualFile::file(full_path, line_ending::normalize(fs.open(&entry.path), line_ending))
+            .with_metadata(metadata.mode, metadata.mtime)
+    }
+}
+
+/// Abstracts over the entry types yielded by [`walkdir`] and [`walkdir_gitignore`] so either can be
+/// turned into a [`VirtualFile`] by [`create_virtual_file_from_dir_entry`]. Only entries from
+/// [`walkdir_gitignore`] are currently passed to it; all other walks go through [`Fs::walk`] and
+/// [`create_virtual_file_from_fs_entry`].
+trait DirEntryLike {
+    fn path(&self) -> &Path;
+    fn is_dir(&self) -> bool;
+    fn mode(&self) -> Option<u32>;
+    fn mtime(&self) -> Option<u64>;
+}
+
+impl DirEntryLike for walkdir::DirEntry {
+    fn path(&self) -> &Path {
+        walkdir::DirEntry::path(self)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    fn mode(&self) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+        self.metadata().ok().map(|m| m.mode())
+    }
+
+    fn mtime(&self) -> Option<u64> {
+        use std::os::unix::fs::MetadataExt;
+        self.metadata().ok().map(|m| m.mtime() as u64)
+    }
+}
+
+impl DirEntryLike for ignore::DirEntry {
+    fn path(&self) -> &Path {
+        ignore::DirEntry::path(self)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_type().map_or(false, |t| t.is_dir())
+    }
+
+    fn mode(&self) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+        self.metadata().ok().map(|m| m.mode())
+    }
+
+    fn mtime(&self) -> Option<u64> {
+        use std::os::unix::fs::MetadataExt;
+        self.metadata().ok().map(|m| m.mtime() as u64)
+    }
+}
+
+/// Per-mount overrides of metadata that would otherwise be copied from the real file system, as
+/// set on a [`CustomDirEntry`] via [`CustomDirEntry::mode`], [`CustomDirEntry::mtime`] and
+/// [`CustomDirEntry::executable`].
+#[derive(Clone, Copy, Debug, Default)]
+struct EntryOverrides {
+    mode: Option<u32>,
+    mtime: Option<u64>,
+    is_executable: bool,
+}
+
+impl EntryOverrides {
+    fn apply(self, w: &mut VirtualFile) {
+        if let Some(mode) = self.mode {
+            // A directory needs its execute ("traversal") bits to stay accessible, even if the
+            // override itself was written with only files in mind (e.g. `0o644`).
+            w.mode = Some(if w.is_dir { mode | 0o111 } else { mode });
+        }
+        if let Some(mtime) = self.mtime {
+            w.mtime = Some(mtime);
+        }
+        if self.is_executable {
+            w.mode = Some(w.mode.unwrap_or(0o644) | 0o111);
+        }
     }
 }
 
 pub struct Mount<'a> {
     point: PathBuf,
     source: &'a MountSource,
+    overrides: EntryOverrides,
 }
 
 impl<'a> Mount<'a> {
-    fn new(full_path: PathBuf, wish: &'a MountSource) -> Mount<'a> {
+    fn new(full_path: PathBuf, wish: &'a MountSource, overrides: EntryOverrides) -> Mount<'a> {
         Self {
             point: full_path,
             source: wish,
+            overrides,
         }
     }
 
@@ -214,15 +853,22 @@ impl<'a> Mount<'a> {
 struct VirtualFile {
     path: PathBuf,
     is_dir: bool,
-    reader: Box<dyn Read>,
+    reader: Box<dyn Read + Send>,
+    /// The Unix file mode, if known or overridden. Written out as the tar header mode and the ZIP
+    /// entry's Unix permissions.
+    mode: Option<u32>,
+    /// The modification time as Unix epoch seconds, if known or overridden.
+    mtime: Option<u64>,
 }
 
 impl VirtualFile {
-    fn file(full_path: PathBuf, reader: impl Read + 'static) -> VirtualFile {
+    fn file(full_path: PathBuf, reader: impl Read + Send + 'static) -> VirtualFile {
         VirtualFile {
             path: full_path,
             is_dir: false,
             reader: Box::new(reader),
+            mode: None,
+            mtime: None,
         }
     }
 
@@ -231,9 +877,17 @@ impl VirtualFile {
             path: full_path,
             is_dir: true,
             reader: Box::new(io::empty()),
+            mode: None,
+            mtime: None,
         }
     }
 
+    fn with_metadata(mut self, mode: Option<u32>, mtime: Option<u64>) -> VirtualFile {
+        self.mode = mode;
+        self.mtime = mtime;
+        self
+    }
+
     fn full_path(&self) -> &Path {
         &self.path
     }
@@ -252,12 +906,51 @@ impl VirtualFile {
 pub struct CustomDirEntry {
     name: PathBuf,
     mount_source: MountSource,
+    mode: Option<u32>,
+    mtime: Option<u64>,
+    is_executable: bool,
 }
 
 impl CustomDirEntry {
     /// Creates a user-defined directory entry with the given name and mount source.
     pub fn new(name: PathBuf, mount_source: MountSource) -> CustomDirEntry {
-        CustomDirEntry { name, mount_source }
+        CustomDirEntry {
+            name,
+            mount_source,
+            mode: None,
+            mtime: None,
+            is_executable: false,
+        }
+    }
+
+    /// Overrides the Unix file mode (e.g. `0o644`) of every entry produced by this mount, instead
+    /// of whatever mode would otherwise be copied from the real file system. Directories always
+    /// get the execute bits (`0o111`) added on top, so they stay traversable.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Overrides the modification time (Unix epoch seconds) of every entry produced by this
+    /// mount, instead of whatever mtime would otherwise be copied from the real file system.
+    pub fn mtime(&mut self, mtime: u64) -> &mut Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// Marks every entry produced by this mount as executable by setting the owner, group and
+    /// other execute bits on top of whatever mode otherwise applies.
+    pub fn executable(&mut self) -> &mut Self {
+        self.is_executable = true;
+        self
+    }
+
+    fn overrides(&self) -> EntryOverrides {
+        EntryOverrides {
+            mode: self.mode,
+            mtime: self.mtime,
+            is_executable: self.is_executable,
+        }
     }
 }
 
@@ -266,6 +959,11 @@ pub fn text(text: impl Into<String>) -> MountSource {
     MountSource::TextContent(text.into())
 }
 
+/// Merges the entries of an existing `.tar`, `.tar.gz` or `.zip` archive at the mount point.
+pub fn merge(archive_path: impl Into<PathBuf>) -> MountSource {
+    MountSource::MergeArchive(archive_path.into())
+}
+
 impl<T: Into<PathBuf>> From<T> for MountSource {
     fn from(value: T) -> Self {
         MountSource::CopyFromPath(value.into())
@@ -1,7 +1,10 @@
 use globset::Glob;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{Error, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use wish_tree::fs::{MemoryFs, OsFs};
+use wish_tree::line_ending::LineEnding;
 use wish_tree::*;
 
 #[test]
@@ -29,3 +32,180 @@ fn basics() {
     dir.render_to_zip(foo_zip_file);
     dir.render_to_tar_gz(foo_tar_gz_file);
 }
+
+#[test]
+fn file_set_honors_exclude_patterns_and_gitignore() {
+    // Given
+    let base = "target/chunk1-src";
+    fs::remove_dir_all(base).ok();
+    fs::create_dir_all(base).unwrap();
+    fs::write(format!("{base}/kept.txt"), "kept").unwrap();
+    fs::write(format!("{base}/build.bak"), "excluded by pattern").unwrap();
+    fs::write(format!("{base}/.gitignore"), "ignored.txt\n").unwrap();
+    fs::write(format!("{base}/ignored.txt"), "excluded by gitignore").unwrap();
+    let mut builder = dir(base);
+    builder.include("**/*").exclude("**/*.bak").respect_gitignore();
+    // When
+    let out = "target/chunk1-out";
+    fs::remove_dir_all(out).ok();
+    let mount: MountSource = (&mut builder).into();
+    mount.render_to_fs(out);
+    // Then
+    assert!(Path::new(&format!("{out}/kept.txt")).exists());
+    assert!(!Path::new(&format!("{out}/build.bak")).exists());
+    assert!(!Path::new(&format!("{out}/ignored.txt")).exists());
+}
+
+#[test]
+fn renders_through_memory_fs_without_touching_disk() {
+    // Given
+    let mut files = BTreeMap::new();
+    files.insert(PathBuf::from("src/a.txt"), b"hello".to_vec());
+    files.insert(PathBuf::from("src/b.txt"), b"world".to_vec());
+    let fs = MemoryFs::with_files(files);
+    let tree = dir! {
+        "out" => "src",
+    };
+    // When
+    tree.render_to_fs_with(&fs, LineEnding::Preserve, "root");
+    // Then
+    let rendered = fs.files();
+    assert_eq!(rendered.get(Path::new("root/out/a.txt")).unwrap(), b"hello");
+    assert_eq!(rendered.get(Path::new("root/out/b.txt")).unwrap(), b"world");
+}
+
+#[test]
+fn renders_xz_and_zstd_tarballs_at_configurable_levels() {
+    // Given
+    let tree = dir! { "notes.txt" => text("hello") };
+    let xz_path = "target/chunk3-test.tar.xz";
+    let zst_path = "target/chunk3-test.tar.zst";
+    // When
+    tree.render_to_tar_xz_with(
+        &OsFs,
+        xz_path,
+        &CompressionOptions {
+            level: 9,
+            xz_dict_size: 1 << 20,
+        },
+        false,
+    );
+    // zstd's own level scale runs well past gzip/xz's 0-9, e.g. up to ~22.
+    tree.render_to_tar_zst_with(
+        &OsFs,
+        zst_path,
+        &CompressionOptions {
+            level: 19,
+            xz_dict_size: 0,
+        },
+        false,
+    );
+    // Then
+    assert!(fs::metadata(xz_path).unwrap().len() > 0);
+    assert!(fs::metadata(zst_path).unwrap().len() > 0);
+}
+
+#[test]
+fn render_to_tar_gz_parallel_reports_progress_events() {
+    // Given
+    let tree = dir! {
+        "a.txt" => text("a"),
+        "b.txt" => text("b"),
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    // When
+    tree.render_to_tar_gz_parallel(
+        &OsFs,
+        "target/chunk4-test.tar.gz",
+        &CompressionOptions::default(),
+        false,
+        Some(tx),
+    );
+    // Then
+    let events: Vec<_> = rx.try_iter().collect();
+    let finished = events
+        .iter()
+        .filter(|e| matches!(e, RenderEvent::EntryFinished { .. }))
+        .count();
+    assert_eq!(finished, 2);
+    assert!(matches!(events.last(), Some(RenderEvent::Done)));
+}
+
+#[test]
+fn merges_archive_and_lists_components_manifest() {
+    // Given
+    let inner = dir! { "hello.txt" => text("hi") };
+    inner.render_to_zip("target/chunk5-inner.zip");
+    let tree = dir! {
+        "vendor" => merge("target/chunk5-inner.zip"),
+    }
+    .with_components_manifest();
+    // When
+    let out = "target/chunk5-out";
+    fs::remove_dir_all(out).ok();
+    tree.render_to_fs(out);
+    // Then
+    let manifest = fs::read_to_string(format!("{out}/components")).unwrap();
+    assert_eq!(manifest, "chunk5-inner.zip");
+    assert_eq!(
+        fs::read_to_string(format!("{out}/vendor/hello.txt")).unwrap(),
+        "hi"
+    );
+}
+
+#[test]
+fn normalizes_line_endings_for_text_content() {
+    // Given
+    let fs = MemoryFs::new();
+    let tree = dir! {
+        "notes.txt" => text("a\r\nb\nc\r"),
+    };
+    // When
+    tree.render_to_fs_with(&fs, LineEnding::Lf, "out");
+    // Then
+    let rendered = fs.files();
+    let content = rendered.get(Path::new("out/notes.txt")).unwrap();
+    assert_eq!(content, b"a\nb\nc\n");
+}
+
+#[test]
+fn detects_path_collisions_and_classifies_mounts() {
+    // Given
+    let tree = dir! {
+        "a" => dir! { "file.txt" => text("from a") },
+        "a/file.txt" => text("direct"),
+    };
+    // When
+    let collisions = tree.validate(&OsFs).unwrap_err();
+    // Then
+    assert_eq!(collisions.len(), 1);
+    assert_eq!(collisions[0].path, PathBuf::from("a/file.txt"));
+    let mount = tree.classify(&OsFs, "a/file.txt").unwrap();
+    assert_eq!(mount.full_path(), Path::new("a/file.txt"));
+    match mount.wish() {
+        MountSource::TextContent(text) => assert_eq!(text, "from a"),
+        other => panic!("unexpected mount source: {other:?}"),
+    }
+}
+
+#[test]
+fn applies_mode_mtime_overrides_and_reproducible_switch() {
+    // Given
+    let mut tree = dir! {
+        "bin/app" => text("#!/bin/sh\necho hi\n"),
+    };
+    if let MountSource::CustomDir(entries) = &mut tree {
+        entries[0].mode(0o755).mtime(12345);
+    }
+    let archive_path = "target/chunk8-test.tar.gz";
+    // When
+    tree.render_to_tar_gz_with(&OsFs, archive_path, &CompressionOptions::default(), true);
+    // Then
+    let file = fs::File::open(archive_path).unwrap();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut entries = archive.entries().unwrap();
+    let entry = entries.next().unwrap().unwrap();
+    assert_eq!(entry.header().mode().unwrap() & 0o777, 0o755);
+    // `reproducible` pins mtime to the epoch even though an explicit mtime override was set.
+    assert_eq!(entry.header().mtime().unwrap(), 0);
+}